@@ -7,77 +7,333 @@ static FEXTRA   : u8 = 0b00000100;
 static FNAME    : u8 = 0b00001000;
 static FCOMMENT : u8 = 0b00010000;
 
+// Maximum number of bits in a DEFLATE Huffman code (RFC1951).
+static MAXBITS : uint = 15;
+
+// A single FEXTRA subfield: a two-byte application id and its raw payload.
+struct ExtraField {
+    id: [u8, ..2],
+    data: Vec<u8>,
+}
+
 struct GzipHeader {
     method: u8,
     flg: u8,
     mtime: u32,
     xfl: u8,
     os: u8,
-    fextra_count: uint,
-    fname: Option<String>,
-    fcomment: Option<String>,
+    // The raw FEXTRA bytes (everything after XLEN), mirroring flate2's
+    // `extra: Option<Vec<u8>>`, plus a parsed view of the subfields.
+    extra: Option<Vec<u8>>,
+    extra_fields: Vec<ExtraField>,
+    // FNAME / FCOMMENT are ISO-8859-1 (Latin-1) per RFC1952, so they are kept
+    // as raw bytes and decoded on demand rather than forced through UTF-8.
+    fname: Option<Vec<u8>>,
+    fcomment: Option<Vec<u8>>,
     fhcrc: Option<u16>
 }
 
+impl GzipHeader {
+    // The raw extra-field bytes, if FEXTRA was present.
+    fn extra(&self) -> Option<&[u8]> {
+        self.extra.as_ref().map(|v| v.as_slice())
+    }
+
+    // The original file name, as raw Latin-1 bytes.
+    fn filename(&self) -> Option<&[u8]> {
+        self.fname.as_ref().map(|v| v.as_slice())
+    }
+
+    // The original file name, losslessly decoded from Latin-1.
+    fn filename_latin1(&self) -> Option<String> {
+        self.fname.as_ref().map(|v| latin1_to_string(v.as_slice()))
+    }
+
+    // The original file name, decoded as strict UTF-8 (errors on invalid
+    // sequences).
+    fn filename_utf8(&self) -> Option<Result<String, String>> {
+        self.fname.as_ref().map(|v| utf8_from_bytes(v.as_slice()))
+    }
+
+    // The comment, as raw Latin-1 bytes.
+    fn comment(&self) -> Option<&[u8]> {
+        self.fcomment.as_ref().map(|v| v.as_slice())
+    }
+
+    // The comment, losslessly decoded from Latin-1.
+    fn comment_latin1(&self) -> Option<String> {
+        self.fcomment.as_ref().map(|v| latin1_to_string(v.as_slice()))
+    }
+
+    // The comment, decoded as strict UTF-8.
+    fn comment_utf8(&self) -> Option<Result<String, String>> {
+        self.fcomment.as_ref().map(|v| utf8_from_bytes(v.as_slice()))
+    }
+}
+
+// CRC32 using the reflected polynomial 0xedb88320, as required by both the
+// gzip trailer and the FHCRC header check.
+struct Crc32 {
+    table: Vec<u32>,
+    value: u32,
+}
+
+impl Crc32 {
+    fn new() -> Crc32 {
+        let mut table = Vec::from_elem(256u, 0u32);
+        for n in range(0u, 256) {
+            let mut c = n as u32;
+            for _ in range(0u, 8) {
+                if c & 1 != 0 {
+                    c = 0xedb88320 ^ (c >> 1);
+                } else {
+                    c = c >> 1;
+                }
+            }
+            table[n] = c;
+        }
+        Crc32 { table: table, value: 0xffffffff }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        let mut c = self.value;
+        for &b in data.iter() {
+            c = (c >> 8) ^ self.table[((c ^ b as u32) & 0xff) as uint];
+        }
+        self.value = c;
+    }
+
+    fn finish(&self) -> u32 {
+        self.value ^ 0xffffffff
+    }
+}
+
+// A canonical Huffman decoder, in the style of Mark Adler's puff.c: `count`
+// holds the number of codes of each length and `symbol` lists the symbols in
+// canonical order so a code can be resolved while reading one bit at a time.
+struct Huffman {
+    count: Vec<int>,
+    symbol: Vec<int>,
+}
+
+impl Huffman {
+    fn new(lengths: &[uint]) -> Result<Huffman, String> {
+        let n = lengths.len();
+
+        let mut count = Vec::from_elem(MAXBITS + 1, 0i);
+        for &len in lengths.iter() {
+            count[len] += 1;
+        }
+
+        // Check the code set for validity the way puff.c's `construct` does:
+        // track the number of available codes at each length. A negative count
+        // means the lengths are over-subscribed; a positive count at the end
+        // means they are incomplete, which is only accepted when at most one
+        // symbol has a code (e.g. a lone distance code, or none at all).
+        let mut left = 1i;
+        for len in range(1u, MAXBITS + 1) {
+            left = left << 1;
+            left = left - count[len];
+            if left < 0 {
+                return Err("over-subscribed huffman code".into_string());
+            }
+        }
+        let used = lengths.iter().filter(|&&l| l != 0).count();
+        if left > 0 && used > 1 {
+            return Err("incomplete huffman code".into_string());
+        }
+
+        // Offset of the first symbol of each length within `symbol`.
+        let mut offs = Vec::from_elem(MAXBITS + 1, 0i);
+        for len in range(1u, MAXBITS) {
+            offs[len + 1] = offs[len] + count[len];
+        }
+
+        let mut symbol = Vec::from_elem(n, 0i);
+        for sym in range(0u, n) {
+            if lengths[sym] != 0 {
+                let len = lengths[sym];
+                symbol[offs[len] as uint] = sym as int;
+                offs[len] += 1;
+            }
+        }
+
+        Ok(Huffman { count: count, symbol: symbol })
+    }
+}
+
 struct GzipReader<'a> {
     reader: &'a mut (Reader + 'a),
+    bitbuf: u32,
+    bitcnt: uint,
+    // When true, decode concatenated members until EOF (RFC1952 allows several
+    // gzip members in one stream); when false, decode a single member and treat
+    // any trailing bytes as an error. Mirrors flate2's `multi` flag.
+    multi: bool,
+    // Pushed-back look-ahead bytes (consumed from the end), used to peek the
+    // magic of a following member without losing it before `read_gzip_header`
+    // runs again.
+    pending: Vec<u8>,
+    // Running CRC32 over the header bytes, for the optional FHCRC check.
+    header_crc: Crc32,
+
+    // --- Incremental inflate state, suspended between `inflate_step` calls ---
+    // Where the block/decode loop currently is within the member.
+    istate: InflateState,
+    // At least the last 32KB of output (up to 64KB, see `emit`), kept so
+    // back-references resolve after the produced bytes have already been
+    // drained to the caller.
+    window: Vec<u8>,
+    // Whether the block being decoded is the final one of the member.
+    inflate_final: bool,
+    // Decoders for the most recent compressed block (None until the member's
+    // first compressed block header has been read).
+    lencode: Option<Huffman>,
+    distcode: Option<Huffman>,
+    // A back-reference copy in progress, carried across `read` boundaries.
+    copy_len: uint,
+    copy_dist: uint,
+    // CRC32 and length of the member body, accumulated as bytes are produced.
+    body_crc: Crc32,
+    body_len: u32,
+}
+
+// Position of the incremental inflate loop within a single gzip member.
+enum InflateState {
+    BlockHeader,       // about to read the next block's header bits
+    Stored(uint),      // inside a stored block, this many bytes left to copy
+    Compressed,        // decoding symbols of a Huffman-coded block
+    MemberDone,        // final block consumed; the trailer still needs checking
 }
 
 impl<'a> GzipReader<'a> {
     fn new(reader: &'a mut Reader) -> GzipReader<'a> {
-        GzipReader { reader: reader }
+        GzipReader {
+            reader: reader,
+            bitbuf: 0,
+            bitcnt: 0,
+            multi: false,
+            pending: Vec::new(),
+            header_crc: Crc32::new(),
+            istate: BlockHeader,
+            window: Vec::new(),
+            inflate_final: false,
+            lencode: None,
+            distcode: None,
+            copy_len: 0,
+            copy_dist: 0,
+            body_crc: Crc32::new(),
+            body_len: 0,
+        }
     }
 
-    fn handle_fextra(&mut self) -> Result<uint, String> {
-        let mut xlen = match self.reader.read_le_u16() {
+    // Opt into concatenated multi-member decoding (default is strict
+    // single-member).
+    fn set_multi(&mut self, multi: bool) {
+        self.multi = multi;
+    }
+
+    // Read a byte, honouring any pushed-back look-ahead byte first.
+    fn read_byte_pending(&mut self) -> std::io::IoResult<u8> {
+        match self.pending.pop() {
+            Some(b) => Ok(b),
+            None => self.reader.read_byte()
+        }
+    }
+
+    // Header-byte reads: like the plain reads but also fold the consumed byte
+    // into `header_crc` so the FHCRC check covers everything from the magic
+    // through the end of the comment field.
+    fn hread_byte(&mut self) -> std::io::IoResult<u8> {
+        let b = try!(self.read_byte_pending());
+        self.header_crc.update(&[b]);
+        Ok(b)
+    }
+
+    fn hread_le_u16(&mut self) -> std::io::IoResult<u16> {
+        let a = try!(self.hread_byte());
+        let b = try!(self.hread_byte());
+        Ok(a as u16 | (b as u16 << 8))
+    }
+
+    fn hread_le_u32(&mut self) -> std::io::IoResult<u32> {
+        let a = try!(self.hread_byte());
+        let b = try!(self.hread_byte());
+        let c = try!(self.hread_byte());
+        let d = try!(self.hread_byte());
+        Ok(a as u32 | (b as u32 << 8) | (c as u32 << 16) | (d as u32 << 24))
+    }
+
+    fn hread_exact(&mut self, n: uint) -> std::io::IoResult<Vec<u8>> {
+        let v = try!(self.reader.read_exact(n));
+        self.header_crc.update(v.as_slice());
+        Ok(v)
+    }
+
+    // Read the FEXTRA field, returning the raw XLEN bytes together with the
+    // subfields parsed out of them so callers can read application-specific
+    // data (e.g. the BGZF `BC` block-size subfield).
+    fn handle_fextra(&mut self) -> Result<(Vec<u8>, Vec<ExtraField>), String> {
+        let xlen = match self.hread_le_u16() {
             Err(e) => return Err(e.description().into_string()),
             Ok(v) => v
         };
-        let mut fextra_count = 0;
-        while xlen > 0 {
-            if xlen < 4 {
+        let raw = match self.hread_exact(xlen as uint) {
+            Err(e) => return Err(e.description().into_string()),
+            Ok(v) => v
+        };
+
+        let mut fields = Vec::new();
+        let mut i = 0u;
+        while i < raw.len() {
+            if raw.len() - i < 4 {
                 return Err("malformed FEXTRA".into_string());
             }
-
-            // two bytes for subfield id
-            match self.reader.read_byte() {
-                Err(e) => return Err(e.description().into_string()),
-                Ok(_) => ()
-            };
-            match self.reader.read_byte() {
-                Err(e) => return Err(e.description().into_string()),
-                Ok(_) => ()
-            };
-
-            let len = match self.reader.read_le_u16() {
-                Err(e) => return Err(e.description().into_string()),
-                Ok(b) => b
-            };
-            xlen = xlen - 4;
-            if xlen < len {
+            let id = [raw[i], raw[i + 1]];
+            let len = raw[i + 2] as uint | (raw[i + 3] as uint << 8);
+            i = i + 4;
+            if raw.len() - i < len {
                 return Err("malformed FEXTRA".into_string());
             }
+            let data = raw.slice(i, i + len).to_vec();
+            fields.push(ExtraField { id: id, data: data });
+            i = i + len;
+        }
+
+        Ok((raw, fields))
+    }
 
-            // subfield itself
-            match self.reader.read_exact(len.to_uint().unwrap()) {
+    // Read a NUL-terminated Latin-1 string field (FNAME/FCOMMENT), hashing the
+    // bytes (including the terminator) into the header CRC as it goes. The
+    // field is capped at 65535 bytes to bound allocation on malformed input.
+    fn read_c_str(&mut self) -> Result<Vec<u8>, String> {
+        let mut chars: Vec<u8> = Vec::new();
+        loop {
+            let c = match self.hread_byte() {
                 Err(e) => return Err(e.description().into_string()),
-                Ok(_) => ()
+                Ok(v) => v
             };
-
-            xlen = xlen - len;
-            fextra_count = fextra_count + 1;
+            if c == 0x00_u8 {
+                break;
+            }
+            if chars.len() >= 65535 {
+                return Err("gzip header field too long".into_string());
+            }
+            chars.push(c);
         }
-        Ok(fextra_count)
+        Ok(chars)
     }
 
     fn read_gzip_header(&mut self) -> Result<GzipHeader, String> {
-        let m1_res     = self.reader.read_byte();
-        let m2_res     = self.reader.read_byte();
-        let method_res = self.reader.read_byte();
-        let flg_res    = self.reader.read_byte();
-        let mtime_res  = self.reader.read_le_u32();
-        let xfl_res    = self.reader.read_byte();
-        let os_res     = self.reader.read_byte();
+        self.header_crc = Crc32::new();
+
+        let m1_res     = self.hread_byte();
+        let m2_res     = self.hread_byte();
+        let method_res = self.hread_byte();
+        let flg_res    = self.hread_byte();
+        let mtime_res  = self.hread_le_u32();
+        let xfl_res    = self.hread_byte();
+        let os_res     = self.hread_byte();
 
         if m1_res.is_err() || m2_res.is_err() ||
             method_res.is_err() ||
@@ -105,33 +361,42 @@ impl<'a> GzipReader<'a> {
             // FTEXT set.
         }
 
-        let fextra_count =
+        let (extra, extra_fields) =
             if flg & FEXTRA != 0 {
-                try!(self.handle_fextra())
+                let (raw, fields) = try!(self.handle_fextra());
+                (Some(raw), fields)
             } else {
-                0
+                (None, Vec::new())
             };
 
         let fname =
             if flg & FNAME != 0 {
-                Some(try!(read_c_utf8_str(&mut self.reader)))
+                Some(try!(self.read_c_str()))
             } else {
                 None
             };
 
         let fcomment =
             if flg & FCOMMENT != 0 {
-                Some(try!(read_c_utf8_str(&mut self.reader)))
+                Some(try!(self.read_c_str()))
             } else {
                 None
             };
 
+        // The CRC16 itself is not part of the header CRC, so snapshot the
+        // running value before reading it.
+        let header_crc32 = self.header_crc.finish();
+
         let fhcrc =
             if flg & FHCRC != 0 {
-                match self.reader.read_le_u16() {
+                let stored = match self.reader.read_le_u16() {
                     Err(e) => return Err(e.description().into_string()),
-                    Ok(v) => Some(v)
+                    Ok(v) => v
+                };
+                if stored != (header_crc32 & 0xffff) as u16 {
+                    return Err("corrupt gzip header (FHCRC mismatch)".into_string());
                 }
+                Some(stored)
             } else {
                 None
             };
@@ -142,28 +407,469 @@ impl<'a> GzipReader<'a> {
             mtime: mtime,
             xfl: xfl,
             os: os,
-            fextra_count: fextra_count,
+            extra: extra,
+            extra_fields: extra_fields,
             fname: fname,
             fcomment: fcomment,
             fhcrc: fhcrc
         })
     }
 
-}
+    // Pull the next bit of the DEFLATE stream, LSB-first, refilling a byte at
+    // a time from the underlying reader.
+    fn read_bit(&mut self) -> Result<u32, String> {
+        if self.bitcnt == 0 {
+            let b = match self.reader.read_byte() {
+                Err(e) => return Err(e.description().into_string()),
+                Ok(v) => v
+            };
+            self.bitbuf = b as u32;
+            self.bitcnt = 8;
+        }
+        let bit = self.bitbuf & 1;
+        self.bitbuf = self.bitbuf >> 1;
+        self.bitcnt = self.bitcnt - 1;
+        Ok(bit)
+    }
+
+    // Read `need` bits as an integer, least-significant bit first.
+    fn read_bits(&mut self, need: uint) -> Result<u32, String> {
+        let mut val = 0u32;
+        for i in range(0u, need) {
+            val = val | (try!(self.read_bit()) << i);
+        }
+        Ok(val)
+    }
+
+    // Resolve one Huffman code against the given decoder, reading bits until
+    // the accumulated code lands inside a length's symbol range.
+    fn decode(&mut self, h: &Huffman) -> Result<int, String> {
+        let mut code  = 0i;
+        let mut first = 0i;
+        let mut index = 0i;
+        for len in range(1u, MAXBITS + 1) {
+            code = code | (try!(self.read_bit()) as int);
+            let count = h.count[len];
+            if code - count < first {
+                return Ok(h.symbol[(index + (code - first)) as uint]);
+            }
+            index = index + count;
+            first = (first + count) << 1;
+            code = code << 1;
+        }
+        Err("invalid huffman code".into_string())
+    }
+
+    // Build literal/length and distance decoders for a type 2 (dynamic
+    // Huffman) block from the code-length codes stored in the stream.
+    fn dynamic_tables(&mut self) -> Result<(Huffman, Huffman), String> {
+        static ORDER : [uint, ..19] =
+            [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+        let hlit  = try!(self.read_bits(5)) as uint + 257;
+        let hdist = try!(self.read_bits(5)) as uint + 1;
+        let hclen = try!(self.read_bits(4)) as uint + 4;
+        if hlit > 286 || hdist > 30 {
+            return Err("too many length or distance codes".into_string());
+        }
+
+        let mut clens = Vec::from_elem(19u, 0u);
+        for i in range(0u, hclen) {
+            clens[ORDER[i]] = try!(self.read_bits(3)) as uint;
+        }
+        let clcode = try!(Huffman::new(clens.as_slice()));
+
+        let mut lengths = Vec::from_elem(hlit + hdist, 0u);
+        let mut index = 0u;
+        while index < hlit + hdist {
+            let sym = try!(self.decode(&clcode));
+            if sym < 16 {
+                lengths[index] = sym as uint;
+                index = index + 1;
+            } else {
+                let mut len = 0u;
+                let rep;
+                if sym == 16 {
+                    if index == 0 {
+                        return Err("repeat with no previous length".into_string());
+                    }
+                    len = lengths[index - 1];
+                    rep = 3 + try!(self.read_bits(2)) as uint;
+                } else if sym == 17 {
+                    rep = 3 + try!(self.read_bits(3)) as uint;
+                } else {
+                    rep = 11 + try!(self.read_bits(7)) as uint;
+                }
+                if index + rep > hlit + hdist {
+                    return Err("too many code lengths".into_string());
+                }
+                for _ in range(0u, rep) {
+                    lengths[index] = len;
+                    index = index + 1;
+                }
+            }
+        }
+
+        let lencode  = try!(Huffman::new(lengths.slice(0, hlit)));
+        let distcode = try!(Huffman::new(lengths.slice(hlit, hlit + hdist)));
+        Ok((lencode, distcode))
+    }
+
+    // Reset the incremental inflate state for a fresh member; call once after
+    // that member's header has been read.
+    fn init_inflate(&mut self) {
+        self.istate = BlockHeader;
+        self.window = Vec::new();
+        self.inflate_final = false;
+        self.lencode = None;
+        self.distcode = None;
+        self.copy_len = 0;
+        self.copy_dist = 0;
+        self.body_crc = Crc32::new();
+        self.body_len = 0;
+    }
+
+    // Emit one decompressed byte: hand it to the caller, append it to the
+    // sliding window (trimmed to stay bounded), and fold it into the running
+    // CRC/length so the trailer can be checked without the whole member in RAM.
+    fn emit(&mut self, b: u8, out: &mut Vec<u8>) {
+        out.push(b);
+        self.window.push(b);
+        self.body_crc.update(&[b]);
+        self.body_len = self.body_len + 1;
+        // Keep at least 32KB (the maximum back-reference distance) but bound the
+        // window by compacting once it has grown to twice that.
+        if self.window.len() >= 65536 {
+            let keep = self.window.slice_from(32768).to_vec();
+            self.window = keep;
+        }
+    }
+
+    // Advance past a finished block, either to the next one or to the trailer.
+    fn finish_block(&mut self) {
+        if self.inflate_final {
+            self.istate = MemberDone;
+        } else {
+            self.istate = BlockHeader;
+        }
+    }
+
+    // Drive the inflate state machine, appending *some* decompressed bytes to
+    // `out` and suspending once it has produced a chunk (so a large member is
+    // never materialized whole). Returns true once the member — including its
+    // trailer — has been fully consumed.
+    fn inflate_step(&mut self, out: &mut Vec<u8>) -> Result<bool, String> {
+        static LENS : [uint, ..29] =
+            [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+             35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+        static LEXT : [uint, ..29] =
+            [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+             3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+        static DISTS : [uint, ..30] =
+            [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+             257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+             8193, 12289, 16385, 24577];
+        static DEXT : [uint, ..30] =
+            [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+             7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+        static CHUNK : uint = 32768;
 
-fn read_c_utf8_str(reader: &mut Reader) -> Result<String, String> {
-    let mut chars: Vec<u8> = Vec::new();
-    loop {
-        let c = reader.read_byte();
-        match c {
-            Ok(0x00_u8) => break,
+        loop {
+            // Finish any back-reference copy left over from a previous call
+            // before doing anything else; copying a byte at a time keeps
+            // overlapping runs (dist < len) correct.
+            while self.copy_len > 0 {
+                let pos = self.window.len() - self.copy_dist;
+                let b = self.window[pos];
+                self.emit(b, out);
+                self.copy_len = self.copy_len - 1;
+                if out.len() >= CHUNK {
+                    return Ok(false);
+                }
+            }
+
+            match self.istate {
+                MemberDone => {
+                    try!(self.check_trailer());
+                    return Ok(true);
+                }
+                BlockHeader => {
+                    let last  = try!(self.read_bit());
+                    let btype = try!(self.read_bits(2));
+                    self.inflate_final = last == 1;
+                    match btype {
+                        0 => {
+                            // Stored block: align to a byte, read LEN/NLEN.
+                            self.bitbuf = 0;
+                            self.bitcnt = 0;
+                            let len = match self.reader.read_le_u16() {
+                                Err(e) => return Err(e.description().into_string()),
+                                Ok(v) => v
+                            };
+                            let nlen = match self.reader.read_le_u16() {
+                                Err(e) => return Err(e.description().into_string()),
+                                Ok(v) => v
+                            };
+                            if len != !nlen {
+                                return Err("stored block length mismatch".into_string());
+                            }
+                            self.istate = Stored(len as uint);
+                        }
+                        1 => {
+                            let (lencode, distcode) = fixed_tables();
+                            self.lencode = Some(lencode);
+                            self.distcode = Some(distcode);
+                            self.istate = Compressed;
+                        }
+                        2 => {
+                            let (lencode, distcode) = try!(self.dynamic_tables());
+                            self.lencode = Some(lencode);
+                            self.distcode = Some(distcode);
+                            self.istate = Compressed;
+                        }
+                        _ => return Err("invalid block type".into_string())
+                    }
+                }
+                Stored(n) => {
+                    if n == 0 {
+                        self.finish_block();
+                    } else {
+                        let b = match self.reader.read_byte() {
+                            Err(e) => return Err(e.description().into_string()),
+                            Ok(v) => v
+                        };
+                        self.emit(b, out);
+                        self.istate = Stored(n - 1);
+                        if out.len() >= CHUNK {
+                            return Ok(false);
+                        }
+                    }
+                }
+                Compressed => {
+                    // Borrow the decoders out of `self` so the bit-reading
+                    // methods can take `&mut self` while we hold them.
+                    let lencode = self.lencode.take().unwrap();
+                    let sym = try!(self.decode(&lencode));
+                    if sym < 256 {
+                        self.lencode = Some(lencode);
+                        self.emit(sym as u8, out);
+                        if out.len() >= CHUNK {
+                            return Ok(false);
+                        }
+                    } else if sym == 256 {
+                        self.lencode = Some(lencode);
+                        self.finish_block();
+                    } else {
+                        let sidx = (sym - 257) as uint;
+                        if sidx >= 29 {
+                            return Err("invalid length code".into_string());
+                        }
+                        let len = LENS[sidx] + try!(self.read_bits(LEXT[sidx])) as uint;
+
+                        let distcode = self.distcode.take().unwrap();
+                        let dsym = try!(self.decode(&distcode)) as uint;
+                        self.distcode = Some(distcode);
+                        if dsym >= 30 {
+                            return Err("invalid distance code".into_string());
+                        }
+                        let dist = DISTS[dsym] + try!(self.read_bits(DEXT[dsym])) as uint;
+                        if dist > self.window.len() {
+                            return Err("distance too far back".into_string());
+                        }
+
+                        // Record the copy; the loop top drains it (possibly
+                        // across several `inflate_step` calls).
+                        self.copy_len = len;
+                        self.copy_dist = dist;
+                        self.lencode = Some(lencode);
+                    }
+                }
+            }
+        }
+    }
+
+    // After a member trailer, decide whether another member follows. Both
+    // magic bytes are peeked so that only a full 0x1f 0x8b pair counts as a new
+    // member: in multi mode any other trailing bytes (including a stray 0x1f)
+    // stop decoding cleanly, while strict mode rejects any trailing data.
+    fn next_member_starts(&mut self) -> Result<bool, String> {
+        let b1 = match self.read_byte_pending() {
+            Ok(b) => b,
+            Err(ref e) if e.kind == std::io::EndOfFile => return Ok(false),
+            Err(e) => return Err(e.description().into_string())
+        };
+        let b2 = match self.read_byte_pending() {
+            Ok(b) => Some(b),
+            Err(ref e) if e.kind == std::io::EndOfFile => None,
+            Err(e) => return Err(e.description().into_string())
+        };
+
+        if !self.multi {
+            return Err("trailing data after gzip member".into_string());
+        }
+        if b1 != 0x1f_u8 || b2 != Some(0x8b_u8) {
+            // Trailing bytes that aren't another member; stop cleanly, the way
+            // concatenated decoding does.
+            return Ok(false);
+        }
+
+        // Push the magic back (popped first-to-last) for `read_gzip_header`.
+        self.pending.push(b2.unwrap());
+        self.pending.push(b1);
+        Ok(true)
+    }
+
+    // Validate the 8-byte gzip footer against the CRC32 and length accumulated
+    // while the member was inflated; ISIZE is the length mod 2^32, and
+    // `body_len` wraps accordingly. Both footer words are little-endian.
+    fn check_trailer(&mut self) -> Result<(), String> {
+        // Any bits left over from the last compressed block are abandoned; the
+        // trailer is byte-aligned.
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+
+        let expected_crc = match self.reader.read_le_u32() {
+            Err(e) => return Err(e.description().into_string()),
+            Ok(v) => v
+        };
+        let expected_isize = match self.reader.read_le_u32() {
             Err(e) => return Err(e.description().into_string()),
-            _ => ()
+            Ok(v) => v
+        };
+
+        if self.body_crc.finish() != expected_crc {
+            return Err("corrupt gzip stream (CRC32 mismatch)".into_string());
+        }
+        if self.body_len != expected_isize {
+            return Err("corrupt gzip stream (length mismatch)".into_string());
         }
-        chars.push(c.unwrap());
+        Ok(())
     }
 
-    match String::from_utf8(chars) {
+}
+
+// The fixed literal/length and distance decoders from RFC1951 section 3.2.6.
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = Vec::from_elem(288u, 0u);
+    for sym in range(0u, 144)   { lengths[sym] = 8; }
+    for sym in range(144u, 256) { lengths[sym] = 9; }
+    for sym in range(256u, 280) { lengths[sym] = 7; }
+    for sym in range(280u, 288) { lengths[sym] = 8; }
+    // The fixed tables are always valid, so construction cannot fail here.
+    let lencode = Huffman::new(lengths.as_slice()).unwrap();
+
+    // All 32 five-bit distance codes, so the code is complete; symbols 30 and
+    // 31 are rejected as invalid when decoded.
+    let dlengths = Vec::from_elem(32u, 5u);
+    let distcode = Huffman::new(dlengths.as_slice()).unwrap();
+
+    (lencode, distcode)
+}
+
+
+// Drives a `GzipReader` as a lazy byte source: the header is parsed on the
+// first `read`, each member's body is inflated on demand, and output is served
+// out of a buffer the way flate2's `GzDecoder` advances a `GzState` enum.
+enum GzState {
+    StateHeader,
+    StateBody,
+    StateFinished,
+}
+
+struct GzDecoder<'a> {
+    inner: GzipReader<'a>,
+    state: GzState,
+    buf: Vec<u8>,
+    pos: uint,
+}
+
+impl<'a> GzDecoder<'a> {
+    fn new(reader: &'a mut Reader) -> GzDecoder<'a> {
+        GzDecoder {
+            inner: GzipReader::new(reader),
+            state: StateHeader,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    // Decode concatenated members instead of failing on trailing data; see
+    // `GzipReader::set_multi`.
+    fn set_multi(&mut self, multi: bool) {
+        self.inner.set_multi(multi);
+    }
+}
+
+// Turn a decoder error string into the kind of `IoError` the `Reader` trait
+// expects callers to see.
+fn decode_io_error(desc: String) -> std::io::IoError {
+    std::io::IoError {
+        kind: std::io::OtherIoError,
+        desc: "gzip decode error",
+        detail: Some(desc),
+    }
+}
+
+impl<'a> Reader for GzDecoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::IoResult<uint> {
+        loop {
+            // Serve whatever is already decompressed before producing more.
+            if self.pos < self.buf.len() {
+                let n = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+                for i in range(0u, n) {
+                    buf[i] = self.buf[self.pos + i];
+                }
+                self.pos = self.pos + n;
+                return Ok(n);
+            }
+
+            match self.state {
+                StateFinished => return Err(std::io::standard_error(std::io::EndOfFile)),
+                StateHeader => {
+                    match self.inner.read_gzip_header() {
+                        Err(e) => return Err(decode_io_error(e)),
+                        Ok(h) => {
+                            if h.method != 8 {
+                                return Err(decode_io_error(
+                                    format!("unsupported compression method 0x{:x}", h.method)));
+                            }
+                        }
+                    }
+                    self.inner.init_inflate();
+                    self.state = StateBody;
+                }
+                StateBody => {
+                    // Produce just the next chunk of this member's output; the
+                    // inflate state is suspended in `self.inner` between calls,
+                    // so the whole member is never buffered at once.
+                    self.buf.clear();
+                    self.pos = 0;
+                    let done = match self.inner.inflate_step(&mut self.buf) {
+                        Err(e) => return Err(decode_io_error(e)),
+                        Ok(d) => d
+                    };
+                    if done {
+                        match self.inner.next_member_starts() {
+                            Err(e) => return Err(decode_io_error(e)),
+                            Ok(true) => self.state = StateHeader,
+                            Ok(false) => self.state = StateFinished,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Losslessly decode Latin-1 (ISO-8859-1) bytes: each byte is the Unicode code
+// point of the same value.
+fn latin1_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// Decode bytes as strict UTF-8.
+fn utf8_from_bytes(bytes: &[u8]) -> Result<String, String> {
+    match String::from_utf8(bytes.to_vec()) {
         Ok(s) => Ok(s),
         Err(_) => Err("expected utf8 string".into_string())
     }
@@ -171,21 +877,12 @@ fn read_c_utf8_str(reader: &mut Reader) -> Result<String, String> {
 
 fn main() {
     let mut reader = BufferedReader::new(std::io::stdio::stdin());
-    let mut gzip_reader = GzipReader::new(&mut reader);
+    let mut decoder = GzDecoder::new(&mut reader);
+    decoder.set_multi(true);
 
-    let header = match gzip_reader.read_gzip_header() {
-        Err(e) => panic!("reading gzip header failed: {}", e),
-        Ok(h) => h
+    let mut stdout = std::io::stdio::stdout();
+    match std::io::util::copy(&mut decoder, &mut stdout) {
+        Err(e) => panic!("decompression failed: {}", e),
+        Ok(_) => ()
     };
-
-    println!("gzip header: method 0x{:x}, flg 0x{:x}, mtime {}, xfl 0x{:x}, os 0x{:x}, fextra_count 0x{:x}, fname {}, fcomment {}, fhcrc {}",
-             header.method,
-             header.flg,
-             header.mtime,
-             header.xfl,
-             header.os,
-             header.fextra_count,
-             header.fname,
-             header.fcomment,
-             header.fhcrc);
 }